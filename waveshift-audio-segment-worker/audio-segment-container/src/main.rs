@@ -2,10 +2,13 @@ use chrono;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
 use serde_json::json;
-use std::io::Write;
-use tempfile::NamedTempFile;
-use tokio::fs;
-use tokio::process::Command;
+
+mod multipart;
+mod pcm;
+mod upload;
+
+const TARGET_SAMPLE_RATE: u32 = 16_000; // 降噪模型要求
+const TARGET_CHANNELS: u16 = 1; // 降噪模型要求单声道
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
@@ -13,6 +16,7 @@ async fn handle_request(req: Request<Body>) -> Result<Response<Body>> {
     match (req.method(), req.uri().path()) {
         (&Method::GET, "/") | (&Method::GET, "/health") => health_check().await,
         (&Method::POST, "/") => process_audio(req).await,
+        (&Method::POST, "/merge") => merge_audio(req).await,
         _ => {
             let mut not_found = Response::default();
             *not_found.status_mut() = StatusCode::NOT_FOUND;
@@ -76,19 +80,19 @@ async fn process_audio(req: Request<Body>) -> Result<Response<Body>> {
 
     println!("📊 时间范围: {}段", time_ranges.len());
 
-    // 获取音频数据
-    let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
-    if body_bytes.is_empty() {
+    // 流式写入临时文件，峰值内存只取决于单个 chunk，而不是整个上传大小
+    let (input_file, received) = upload::stream_to_tempfile(req).await?;
+    if received == 0 {
         return Ok(Response::builder()
             .status(StatusCode::BAD_REQUEST)
             .header("Content-Type", "application/json")
             .body(Body::from(json!({"success": false, "error": "No audio data received"}).to_string()))?);
     }
 
-    println!("📥 接收音频数据: {} bytes", body_bytes.len());
+    println!("📥 接收音频数据: {} bytes", received);
 
     // 执行 FFmpeg 处理
-    match execute_ffmpeg_for_ranges(&body_bytes, &time_ranges, gap_duration_ms).await {
+    match execute_ffmpeg_for_ranges(input_file.path(), &time_ranges, gap_duration_ms).await {
         Ok(output_data) => {
             println!("✅ FFmpeg处理完成: 输出 {} bytes", output_data.len());
             
@@ -111,118 +115,177 @@ async fn process_audio(req: Request<Body>) -> Result<Response<Body>> {
 }
 
 async fn execute_ffmpeg_for_ranges(
-    audio_data: &[u8],
+    input_path: &std::path::Path,
     time_ranges: &[Vec<i32>],
     gap_duration_ms: i32,
 ) -> Result<Vec<u8>> {
-    // 创建临时输入文件 (指定AAC扩展名帮助FFmpeg识别格式)
-    let mut input_file = NamedTempFile::with_suffix(".aac")?;
-    input_file.write_all(audio_data)?;
-    let input_path = input_file.path();
-
-    // 创建临时输出文件 (指定WAV扩展名以便FFmpeg推断输出格式)
-    let output_file = NamedTempFile::with_suffix(".wav")?;
-    let output_path = output_file.path();
-
-    let result = if time_ranges.len() == 1 {
-        // 🎯 单段处理 - 高性能流复制
-        let start_ms = time_ranges[0][0];
-        let end_ms = time_ranges[0][1];
-        let start_sec = start_ms as f64 / 1000.0;
-        let duration_sec = (end_ms - start_ms) as f64 / 1000.0;
-
-        println!("📝 单段FFmpeg: {:.3}s-{:.3}s ({:.3}s)", 
-                 start_sec, start_sec + duration_sec, duration_sec);
-
-        Command::new("ffmpeg")
-            .args(&[
-                "-y",
-                "-ss", &format!("{:.3}", start_sec),
-                "-i", input_path.to_str().unwrap(),
-                "-t", &format!("{:.3}", duration_sec),
-                "-ar", "16000",       // 🆕 重采样到16kHz (降噪模型要求)
-                "-ac", "1",           // 🆕 转换为单声道 (降噪模型要求)
-                "-c:a", "pcm_s16le",  // 明确指定WAV编码格式
-                "-f", "wav",          // 明确指定输出格式
-                "-avoid_negative_ts", "make_zero",
-                output_path.to_str().unwrap(),
-            ])
-            .output()
-            .await?
-    } else {
-        // 🎵 多段处理 - Gap静音插入
-        let mut ffmpeg_cmd = Command::new("ffmpeg");
-        ffmpeg_cmd.arg("-y");
-
-        // 为每个音频段添加输入
+    let input_path = input_path.to_path_buf();
+    let time_ranges = time_ranges.to_vec();
+
+    // 解码、切片、拼接都是 CPU 密集型工作，丢给阻塞线程池
+    tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+        // 🎯 整段解码一次到目标采样率/单声道的 PCM，再直接按毫秒切片，
+        // 避免为每个 range 单独起一次 demux/decode
+        let samples = pcm::decode_to_pcm(&input_path, TARGET_SAMPLE_RATE, TARGET_CHANNELS)?;
+
+        let mut out = Vec::new();
         for (i, range) in time_ranges.iter().enumerate() {
             let start_ms = range[0];
             let end_ms = range[1];
-            let start_sec = start_ms as f64 / 1000.0;
-            let duration_sec = (end_ms - start_ms) as f64 / 1000.0;
-
-            ffmpeg_cmd.args(&[
-                "-ss", &format!("{:.3}", start_sec),
-                "-t", &format!("{:.3}", duration_sec),
-                "-i", input_path.to_str().unwrap(),
-            ]);
+            println!(
+                "📝 段{}: {:.3}s-{:.3}s ({:.3}s)",
+                i + 1,
+                start_ms as f64 / 1000.0,
+                end_ms as f64 / 1000.0,
+                (end_ms - start_ms) as f64 / 1000.0
+            );
+
+            let start = (start_ms as i64 * TARGET_SAMPLE_RATE as i64 / 1000).max(0) as usize
+                * TARGET_CHANNELS as usize;
+            let end = ((end_ms as i64 * TARGET_SAMPLE_RATE as i64 / 1000) as usize
+                * TARGET_CHANNELS as usize)
+                .min(samples.len());
+            if start < end {
+                out.extend_from_slice(&samples[start..end]);
+            }
 
-            println!("  段{}: {:.3}s-{:.3}s ({:.3}s)", 
-                     i + 1, start_sec, start_sec + duration_sec, duration_sec);
+            if i + 1 < time_ranges.len() {
+                out.extend(pcm::silence(gap_duration_ms, TARGET_SAMPLE_RATE, TARGET_CHANNELS));
+            }
         }
 
-        // 构建filter_complex - Gap静音插入
-        let gap_sec = gap_duration_ms as f64 / 1000.0;
-        let gap_filter = format!("anullsrc=channel_layout=mono:sample_rate=44100:duration={:.3}", gap_sec);
+        if time_ranges.len() > 1 {
+            println!(
+                "🎵 多段处理: {}段 + {}个Gap({:.3}s)",
+                time_ranges.len(),
+                time_ranges.len() - 1,
+                gap_duration_ms as f64 / 1000.0
+            );
+        }
 
-        // 构建拼接序列：音频1 + gap + 音频2 + gap + 音频3...
-        let mut concat_parts = Vec::new();
-        for i in 0..time_ranges.len() {
-            concat_parts.push(format!("[{}:a]", i));
-            if i < time_ranges.len() - 1 {
-                concat_parts.push("[gap]".to_string());
-            }
+        if out.is_empty() {
+            return Err("解码/切片后没有产生任何样本".into());
         }
 
-        let filter_complex = format!(
-            "{}[gap];{}concat=n={}:v=0:a=1[out]",
-            gap_filter,
-            concat_parts.join(""),
-            concat_parts.len()
-        );
-
-        println!("🎵 多段处理: {}段 + {}个Gap({:.3}s)", 
-                 time_ranges.len(), time_ranges.len() - 1, gap_sec);
-
-        ffmpeg_cmd
-            .args(&["-filter_complex", &filter_complex])
-            .args(&["-map", "[out]"])
-            .args(&["-ar", "16000"])       // 🆕 重采样到16kHz (降噪模型要求)
-            .args(&["-ac", "1"])           // 🆕 转换为单声道 (降噪模型要求)
-            .args(&["-c:a", "pcm_s16le"])  // 明确指定WAV编码格式
-            .args(&["-f", "wav"])          // 明确指定输出格式
-            .arg(output_path.to_str().unwrap())
-            .output()
-            .await?
+        let wav = pcm::write_wav(&out, TARGET_SAMPLE_RATE, TARGET_CHANNELS);
+        println!("🎉 处理成功: 生成 {} bytes", wav.len());
+        Ok(wav)
+    })
+    .await?
+}
+
+/// `POST /merge` — accepts several heterogeneous audio clips (different
+/// sample rates, channel counts, codecs) as named parts of one multipart
+/// request and concatenates them, instead of slicing ranges from a single
+/// input like `/` does. Each clip is resampled to a common target before
+/// joining since `concat` requires uniform parameters.
+async fn merge_audio(req: Request<Body>) -> Result<Response<Body>> {
+    let (target_rate, gap_duration_ms, crossfade_ms, content_type) = {
+        let headers = req.headers();
+
+        let target_rate: u32 = headers
+            .get("x-target-rate")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(TARGET_SAMPLE_RATE);
+
+        let gap_duration_ms: i32 = headers
+            .get("x-gap-duration")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(500);
+
+        // 0 (默认) 表示用静音 gap 拼接；>0 表示改用等长的 acrossfade 交叉淡化
+        let crossfade_ms: i32 = headers
+            .get("x-crossfade-duration")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let content_type = headers
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .ok_or("Missing Content-Type header")?
+            .to_string();
+
+        (target_rate, gap_duration_ms, crossfade_ms, content_type)
     };
 
-    // 检查 FFmpeg 执行结果
-    if !result.status.success() {
-        let error_msg = String::from_utf8_lossy(&result.stderr);
-        return Err(format!("FFmpeg failed: {}", error_msg).into());
+    println!(
+        "🎛️ 合并音频片段: target_rate={}Hz, gap={}ms, crossfade={}ms",
+        target_rate, gap_duration_ms, crossfade_ms
+    );
+
+    let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+    let clips: Vec<Vec<u8>> = multipart::parse(&content_type, &body_bytes)?
+        .into_iter()
+        .filter(|part| part.filename.is_some())
+        .map(|part| part.data)
+        .collect();
+
+    if clips.is_empty() {
+        return Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json!({"success": false, "error": "No audio clips received"}).to_string()))?);
     }
 
-    // 验证输出文件
-    let metadata = fs::metadata(output_path).await?;
-    if metadata.len() == 0 {
-        return Err("FFmpeg produced empty output file".into());
+    println!("📊 待合并片段数: {}", clips.len());
+
+    match execute_ffmpeg_merge(clips, target_rate, gap_duration_ms, crossfade_ms).await {
+        Ok(output_data) => {
+            println!("✅ 合并完成: 输出 {} bytes", output_data.len());
+
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "audio/wav")
+                .header("X-Processing-Success", "true")
+                .body(Body::from(output_data))?)
+        }
+        Err(e) => {
+            eprintln!("❌ 合并失败: {}", e);
+            Ok(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header("Content-Type", "application/json")
+                .body(Body::from(json!({"success": false, "error": format!("Audio merge failed: {}", e)}).to_string()))?)
+        }
     }
+}
 
-    // 读取处理后的音频数据
-    let output_data = fs::read(output_path).await?;
-    println!("🎉 FFmpeg处理成功: 生成 {} bytes", output_data.len());
+async fn execute_ffmpeg_merge(
+    clips: Vec<Vec<u8>>,
+    target_rate: u32,
+    gap_duration_ms: i32,
+    crossfade_ms: i32,
+) -> Result<Vec<u8>> {
+    tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+        let mut merged: Vec<i16> = Vec::new();
+        for (i, clip) in clips.iter().enumerate() {
+            // 每段各自可能是不同采样率/声道/编码，先落盘再按统一目标参数解码，
+            // 复用 decode_to_pcm 现成的 demux/decode/resample 流程
+            let tmp = tempfile::NamedTempFile::new()?;
+            std::fs::write(tmp.path(), clip)?;
+            let samples = pcm::decode_to_pcm(tmp.path(), target_rate, TARGET_CHANNELS)?;
+
+            if i == 0 {
+                merged = samples;
+            } else if crossfade_ms > 0 {
+                merged = pcm::crossfade_concat(&merged, &samples, crossfade_ms, target_rate, TARGET_CHANNELS);
+            } else {
+                merged.extend(pcm::silence(gap_duration_ms, target_rate, TARGET_CHANNELS));
+                merged.extend_from_slice(&samples);
+            }
+        }
 
-    Ok(output_data)
+        if merged.is_empty() {
+            return Err("合并后没有产生任何样本".into());
+        }
+
+        let wav = pcm::write_wav(&merged, target_rate, TARGET_CHANNELS);
+        println!("🎉 合并成功: 生成 {} bytes", wav.len());
+        Ok(wav)
+    })
+    .await?
 }
 
 #[tokio::main]
@@ -231,24 +294,12 @@ async fn main() -> Result<()> {
     println!("📋 系统信息:");
     println!("  - 监听端口: 8080");
     println!("  - 架构: Rust + Alpine Linux");
-    println!("  - 支持端点: / (GET健康检查, POST音频处理)");
-
-    // 检查 FFmpeg 是否可用
-    match Command::new("ffmpeg").arg("-version").output().await {
-        Ok(output) => {
-            if output.status.success() {
-                let version_info = String::from_utf8_lossy(&output.stdout);
-                let first_line = version_info.lines().next().unwrap_or("未知版本");
-                println!("✅ FFmpeg检查通过: {}", first_line);
-            } else {
-                eprintln!("❌ FFmpeg版本检查失败");
-                return Err("FFmpeg不可用".into());
-            }
-        }
-        Err(e) => {
-            eprintln!("❌ 无法执行FFmpeg: {}", e);
-            return Err("FFmpeg不可执行".into());
-        }
+    println!("  - 处理方式: 进程内 libav（无子进程；上传流式写入临时文件后直接解码）");
+    println!("  - 支持端点: / (GET健康检查, POST音频处理), /merge (POST多片段合并)");
+    unsafe {
+        let version = ffmpeg_sys_next::av_version_info();
+        let version = std::ffi::CStr::from_ptr(version).to_string_lossy();
+        println!("  - libav 版本: {}", version);
     }
 
     // 创建服务
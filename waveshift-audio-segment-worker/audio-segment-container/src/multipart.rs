@@ -0,0 +1,126 @@
+//! Minimal `multipart/form-data` request parser for `/merge`, which (unlike
+//! `/`) needs several named clips out of one body rather than one whole
+//! file. Splits on the boundary from `Content-Type` and picks each part's
+//! `name`/`filename`/bytes out of its header block by hand — this crate has
+//! no multipart dependency and the other endpoints don't need one.
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Pulls `boundary=...` out of a `multipart/form-data; boundary=...`
+/// `Content-Type` header value, unquoting it if the client quoted it.
+fn boundary_from_content_type(content_type: &str) -> Result<String> {
+    content_type
+        .split(';')
+        .map(str::trim)
+        .find_map(|segment| segment.strip_prefix("boundary="))
+        .map(|b| b.trim_matches('"').to_string())
+        .ok_or_else(|| "Content-Type is missing a multipart boundary".into())
+}
+
+/// Finds `needle` in `haystack` starting at or after `from`, returning its
+/// start offset.
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack[from..]
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| i + from)
+}
+
+/// Pulls `key="value"` out of a `Content-Disposition: form-data; key="value"`
+/// header line.
+fn header_param(header_line: &str, key: &str) -> Option<String> {
+    let marker = format!("{key}=\"");
+    let start = header_line.find(&marker)? + marker.len();
+    let end = header_line[start..].find('"')? + start;
+    Some(header_line[start..end].to_string())
+}
+
+/// Parses every part out of a full `multipart/form-data` body.
+pub fn parse(content_type: &str, body: &[u8]) -> Result<Vec<Part>> {
+    let boundary = boundary_from_content_type(content_type)?;
+    let delimiter = format!("--{boundary}").into_bytes();
+    let mut parts = Vec::new();
+
+    let mut pos = find(body, &delimiter, 0).ok_or("multipart body has no boundary")?;
+    loop {
+        pos += delimiter.len();
+        if body[pos..].starts_with(b"--") {
+            break; // closing delimiter
+        }
+        let next = find(body, &delimiter, pos).ok_or("unterminated multipart part")?;
+        let part_bytes = &body[pos..next];
+
+        let header_end = find(part_bytes, b"\r\n\r\n", 0).ok_or("multipart part has no header block")?;
+        let header_block = String::from_utf8_lossy(&part_bytes[..header_end]);
+        // trailing "\r\n" before the next boundary's leading "--" belongs to
+        // the delimiter, not the payload
+        let data_start = header_end + 4;
+        let data_end = part_bytes.len().saturating_sub(2);
+        let data = part_bytes[data_start..data_end].to_vec();
+
+        let disposition = header_block
+            .lines()
+            .find(|line| line.to_ascii_lowercase().starts_with("content-disposition:"))
+            .ok_or("multipart part has no Content-Disposition header")?;
+        let name = header_param(disposition, "name").ok_or("multipart part has no name")?;
+        let filename = header_param(disposition, "filename");
+
+        parts.push(Part { name, filename, data });
+        pos = next;
+    }
+
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_known_multipart_body() {
+        let boundary = "X-BOUNDARY";
+        let body = format!(
+            "--{boundary}\r\n\
+             Content-Disposition: form-data; name=\"clip\"; filename=\"a.wav\"\r\n\
+             Content-Type: audio/wav\r\n\
+             \r\n\
+             \x01\x02\x03\r\n\
+             --{boundary}\r\n\
+             Content-Disposition: form-data; name=\"gap_ms\"\r\n\
+             \r\n\
+             50\r\n\
+             --{boundary}--\r\n"
+        );
+
+        let parts = parse(
+            &format!("multipart/form-data; boundary={boundary}"),
+            body.as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "clip");
+        assert_eq!(parts[0].filename.as_deref(), Some("a.wav"));
+        assert_eq!(parts[0].data, vec![1, 2, 3]);
+        assert_eq!(parts[1].name, "gap_ms");
+        assert_eq!(parts[1].filename, None);
+        assert_eq!(parts[1].data, b"50");
+    }
+
+    #[test]
+    fn accepts_a_quoted_boundary() {
+        let boundary = boundary_from_content_type("multipart/form-data; boundary=\"abc123\"").unwrap();
+        assert_eq!(boundary, "abc123");
+    }
+
+    #[test]
+    fn rejects_a_content_type_without_a_boundary() {
+        assert!(boundary_from_content_type("multipart/form-data").is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! In-process decode → resample pipeline. Replaces the `ffmpeg` subprocess +
+//! temp-file round trip in `execute_ffmpeg_for_ranges` with a single
+//! demux/decode pass: the whole clip is decoded once into `i16` PCM at the
+//! target rate/channel count, ranges are sliced directly out of that buffer
+//! (with silence spliced in between), and the result is wrapped in a
+//! hand-rolled WAV header.
+
+use ffmpeg_sys_next::*;
+use std::ptr;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Owns every libav handle a decode can allocate and frees whichever of
+/// them are non-null on `Drop`, so a `?` on any decode step — open, codec
+/// setup, resampler setup, or the read/decode loop — can't leak a format
+/// context, codec context, resampler, packet, or frame for a malformed
+/// clip. Mirrors the Drop-based cleanup the separation container's
+/// `Demuxer`/`Muxer` (its `container.rs`) use.
+#[derive(Default)]
+struct DecodeGuard {
+    fmt_ctx: *mut AVFormatContext,
+    dec_ctx: *mut AVCodecContext,
+    swr: *mut SwrContext,
+    pkt: *mut AVPacket,
+    frame: *mut AVFrame,
+}
+
+impl Drop for DecodeGuard {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.frame.is_null() {
+                av_frame_free(&mut self.frame);
+            }
+            if !self.pkt.is_null() {
+                av_packet_free(&mut self.pkt);
+            }
+            if !self.swr.is_null() {
+                swr_free(&mut self.swr);
+            }
+            if !self.dec_ctx.is_null() {
+                avcodec_free_context(&mut self.dec_ctx);
+            }
+            if !self.fmt_ctx.is_null() {
+                avformat_close_input(&mut self.fmt_ctx);
+            }
+        }
+    }
+}
+
+/// Decodes the (single-stream) audio at `input_path` to interleaved `i16`
+/// PCM at `target_rate` Hz / `target_channels` channels, via `libavcodec` +
+/// `libswresample`. Opens `input_path` through libav's own file protocol —
+/// no custom AVIO, no whole-upload buffer in RAM (see `crate::upload`,
+/// which streams the request body to this path as it arrives).
+pub fn decode_to_pcm(
+    input_path: &std::path::Path,
+    target_rate: u32,
+    target_channels: u16,
+) -> Result<Vec<i16>> {
+    unsafe {
+        let mut g = DecodeGuard::default();
+
+        let path_c = std::ffi::CString::new(input_path.to_string_lossy().as_bytes())?;
+        let ret = avformat_open_input(&mut g.fmt_ctx, path_c.as_ptr(), ptr::null(), ptr::null_mut());
+        if ret < 0 {
+            return Err(format!("avformat_open_input failed: {ret}").into());
+        }
+        let ret = avformat_find_stream_info(g.fmt_ctx, ptr::null_mut());
+        if ret < 0 {
+            return Err(format!("avformat_find_stream_info failed: {ret}").into());
+        }
+
+        let stream_index = {
+            let streams = std::slice::from_raw_parts(
+                (*g.fmt_ctx).streams,
+                (*g.fmt_ctx).nb_streams as usize,
+            );
+            streams
+                .iter()
+                .position(|&s| (*(*s).codecpar).codec_type == AVMediaType::AVMEDIA_TYPE_AUDIO)
+                .ok_or("input has no audio stream")?
+        };
+        let in_stream = *(*g.fmt_ctx).streams.add(stream_index);
+        let codecpar = (*in_stream).codecpar;
+
+        let decoder = avcodec_find_decoder((*codecpar).codec_id);
+        if decoder.is_null() {
+            return Err("no decoder available for input audio codec".into());
+        }
+        g.dec_ctx = avcodec_alloc_context3(decoder);
+        avcodec_parameters_to_context(g.dec_ctx, codecpar);
+        let ret = avcodec_open2(g.dec_ctx, decoder, ptr::null_mut());
+        if ret < 0 {
+            return Err(format!("avcodec_open2 failed: {ret}").into());
+        }
+
+        let in_layout = if (*codecpar).channel_layout != 0 {
+            (*codecpar).channel_layout as i64
+        } else {
+            av_get_default_channel_layout((*codecpar).channels)
+        };
+        let out_layout = av_get_default_channel_layout(target_channels as i32);
+
+        g.swr = swr_alloc_set_opts(
+            ptr::null_mut(),
+            out_layout,
+            AVSampleFormat::AV_SAMPLE_FMT_S16,
+            target_rate as i32,
+            in_layout,
+            (*g.dec_ctx).sample_fmt,
+            (*g.dec_ctx).sample_rate,
+            0,
+            ptr::null_mut(),
+        );
+        if g.swr.is_null() {
+            return Err("swr_alloc_set_opts failed".into());
+        }
+        if swr_init(g.swr) < 0 {
+            return Err("swr_init failed".into());
+        }
+
+        let mut pcm: Vec<i16> = Vec::new();
+        g.pkt = av_packet_alloc();
+        g.frame = av_frame_alloc();
+
+        loop {
+            let ret = av_read_frame(g.fmt_ctx, g.pkt);
+            if ret == AVERROR_EOF {
+                break;
+            }
+            if ret < 0 {
+                return Err(format!("av_read_frame failed: {ret}").into());
+            }
+            if (*g.pkt).stream_index as usize != stream_index {
+                av_packet_unref(g.pkt);
+                continue;
+            }
+            if avcodec_send_packet(g.dec_ctx, g.pkt) < 0 {
+                av_packet_unref(g.pkt);
+                continue;
+            }
+            av_packet_unref(g.pkt);
+
+            loop {
+                let ret = avcodec_receive_frame(g.dec_ctx, g.frame);
+                if ret == AVERROR(EAGAIN) || ret == AVERROR_EOF {
+                    break;
+                }
+                if ret < 0 {
+                    return Err(format!("avcodec_receive_frame failed: {ret}").into());
+                }
+                resample_frame_into(g.swr, g.frame, target_channels, &mut pcm)?;
+            }
+        }
+
+        // 冲洗 swresample 内部缓冲的剩余样本
+        flush_resampler(g.swr, target_channels, &mut pcm)?;
+
+        Ok(pcm)
+    }
+}
+
+unsafe fn resample_frame_into(
+    swr: *mut SwrContext,
+    frame: *mut AVFrame,
+    target_channels: u16,
+    out: &mut Vec<i16>,
+) -> Result<()> {
+    let max_out_samples = swr_get_out_samples(swr, (*frame).nb_samples) as usize;
+    let mut buf = vec![0i16; max_out_samples * target_channels as usize];
+    let mut out_ptr = buf.as_mut_ptr() as *mut u8;
+    let converted = swr_convert(
+        swr,
+        &mut out_ptr,
+        max_out_samples as i32,
+        (*frame).extended_data as *mut *const u8,
+        (*frame).nb_samples,
+    );
+    if converted < 0 {
+        return Err("swr_convert failed".into());
+    }
+    buf.truncate(converted as usize * target_channels as usize);
+    out.extend_from_slice(&buf);
+    Ok(())
+}
+
+unsafe fn flush_resampler(swr: *mut SwrContext, target_channels: u16, out: &mut Vec<i16>) -> Result<()> {
+    loop {
+        let max_out_samples = swr_get_out_samples(swr, 0) as usize;
+        if max_out_samples == 0 {
+            break;
+        }
+        let mut buf = vec![0i16; max_out_samples * target_channels as usize];
+        let mut out_ptr = buf.as_mut_ptr() as *mut u8;
+        let converted = swr_convert(swr, &mut out_ptr, max_out_samples as i32, ptr::null(), 0);
+        if converted <= 0 {
+            break;
+        }
+        buf.truncate(converted as usize * target_channels as usize);
+        out.extend_from_slice(&buf);
+    }
+    Ok(())
+}
+
+/// `duration_ms` of silence at `sample_rate`/`channels`, interleaved `i16`.
+pub fn silence(duration_ms: i32, sample_rate: u32, channels: u16) -> Vec<i16> {
+    let samples = (duration_ms.max(0) as i64 * sample_rate as i64 / 1000) as usize;
+    vec![0i16; samples * channels as usize]
+}
+
+/// Joins `a` and `b` with a linear `acrossfade`-style crossfade over the
+/// last/first `duration_ms` of each, instead of a hard silent gap: `a`'s
+/// tail fades out while `b`'s head fades in, and the overlap is summed
+/// rather than concatenated. Both inputs must already share `sample_rate`/
+/// `channels` (callers resample to the merge target before calling this).
+pub fn crossfade_concat(a: &[i16], b: &[i16], duration_ms: i32, sample_rate: u32, channels: u16) -> Vec<i16> {
+    let requested = (duration_ms.max(0) as i64 * sample_rate as i64 / 1000) as usize * channels as usize;
+    let n = requested.min(a.len()).min(b.len());
+
+    let mut out = Vec::with_capacity(a.len() + b.len() - n);
+    out.extend_from_slice(&a[..a.len() - n]);
+    for i in 0..n {
+        let t = i as f32 / n as f32;
+        let mixed = a[a.len() - n + i] as f32 * (1.0 - t) + b[i] as f32 * t;
+        out.push(mixed.clamp(i16::MIN as f32, i16::MAX as f32) as i16);
+    }
+    out.extend_from_slice(&b[n..]);
+    out
+}
+
+/// Wraps interleaved `i16` PCM in a minimal 44-byte canonical WAV header.
+pub fn write_wav(samples: &[i16], sample_rate: u32, channels: u16) -> Vec<u8> {
+    let bytes_per_sample = 2u32;
+    let data_len = (samples.len() as u32) * bytes_per_sample;
+    let byte_rate = sample_rate * channels as u32 * bytes_per_sample;
+    let block_align = channels * bytes_per_sample as u16;
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&byte_rate.to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_wav_emits_a_canonical_44_byte_header() {
+        let samples = [1i16, -1, 2, -2];
+        let wav = write_wav(&samples, 16_000, 1);
+
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 36 + 8);
+        assert_eq!(&wav[8..12], b"WAVE");
+        assert_eq!(&wav[12..16], b"fmt ");
+        assert_eq!(u32::from_le_bytes(wav[16..20].try_into().unwrap()), 16);
+        assert_eq!(u16::from_le_bytes(wav[20..22].try_into().unwrap()), 1); // PCM
+        assert_eq!(u16::from_le_bytes(wav[22..24].try_into().unwrap()), 1); // channels
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 16_000); // sample rate
+        assert_eq!(u32::from_le_bytes(wav[28..32].try_into().unwrap()), 32_000); // byte rate
+        assert_eq!(u16::from_le_bytes(wav[32..34].try_into().unwrap()), 2); // block align
+        assert_eq!(u16::from_le_bytes(wav[34..36].try_into().unwrap()), 16); // bits per sample
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 8);
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[44..], [1i16, -1, 2, -2].map(i16::to_le_bytes).concat());
+    }
+
+    #[test]
+    fn silence_produces_the_requested_sample_count() {
+        let samples = silence(500, 16_000, 2);
+        assert_eq!(samples.len(), 16_000 / 2 * 2);
+        assert!(samples.iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn silence_of_zero_duration_is_empty() {
+        assert!(silence(0, 16_000, 1).is_empty());
+    }
+
+    #[test]
+    fn crossfade_concat_shortens_by_the_overlap_length() {
+        let a = vec![100i16; 1_600]; // 100ms @ 16kHz mono
+        let b = vec![-100i16; 1_600];
+        let joined = crossfade_concat(&a, &b, 50, 16_000, 1);
+
+        let overlap = 16_000 * 50 / 1000;
+        assert_eq!(joined.len(), a.len() + b.len() - overlap);
+        // Outside the overlap both clips keep their original samples.
+        assert_eq!(joined[0], 100);
+        assert_eq!(*joined.last().unwrap(), -100);
+    }
+
+    #[test]
+    fn crossfade_concat_caps_overlap_to_the_shorter_clip() {
+        let a = vec![1i16; 10];
+        let b = vec![2i16; 10];
+        // Requesting a 1s crossfade on 10-sample clips should clamp to 10.
+        let joined = crossfade_concat(&a, &b, 1_000, 16_000, 1);
+        assert_eq!(joined.len(), 10);
+    }
+}
@@ -0,0 +1,28 @@
+//! Streams an incoming request body straight to a temp file as chunks
+//! arrive, instead of `hyper::body::to_bytes` buffering the whole upload in
+//! RAM first. Peak memory is bounded by one chunk, not by upload size.
+
+use hyper::body::HttpBody;
+use hyper::{Body, Request};
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Drains `req`'s body into a new temp file, returning it (still open, so it
+/// isn't unlinked out from under the caller) plus the total byte count.
+pub async fn stream_to_tempfile(req: Request<Body>) -> Result<(NamedTempFile, u64)> {
+    let tmp = NamedTempFile::new()?;
+    let mut file = tokio::fs::File::create(tmp.path()).await?;
+    let mut body = req.into_body();
+    let mut total: u64 = 0;
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        total += chunk.len() as u64;
+    }
+    file.flush().await?;
+
+    Ok((tmp, total))
+}
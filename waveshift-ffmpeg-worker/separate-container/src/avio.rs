@@ -0,0 +1,125 @@
+//! In-process AVIO plumbing.
+//!
+//! Wraps libav's custom-IO callbacks around an owned Rust value so the
+//! mux stages never touch the filesystem or shell out to the `ffmpeg`
+//! binary. `AvioContext<T>` is generic over the opaque payload handed to
+//! `avio_alloc_context` — `MemoryIo` below backs the split muxers' output
+//! (the DASH packager in `crate::dash` opens its own per-file `ChildIo`
+//! contexts instead); `crate::stream_io::ChannelIo` backs the live SRT ingest path's
+//! demuxer input with the same allocate/free discipline but a channel
+//! instead of a `Vec`. Demuxer input otherwise goes through libav's own
+//! file protocol (see `crate::container::Demuxer::open_file`) rather than
+//! a custom read callback. Whichever `T` is used, `AvioContext` owns the
+//! allocated buffer/context pair and frees both on `Drop`.
+
+use ffmpeg_sys_next::*;
+use std::ffi::c_void;
+use std::os::raw::c_int;
+
+pub(crate) const AVIO_BUFFER_SIZE: usize = 64 * 1024;
+
+type ReadCb = unsafe extern "C" fn(*mut c_void, *mut u8, c_int) -> c_int;
+type WriteCb = unsafe extern "C" fn(*mut c_void, *mut u8, c_int) -> c_int;
+type SeekCb = unsafe extern "C" fn(*mut c_void, i64, c_int) -> i64;
+
+/// Backing store for a muxer's output: `write_packet` overwrites `output`
+/// at `pos`, growing it as needed, and `seek` repositions `pos` — including
+/// backwards, which is what lets `seek` patch bytes a muxer already wrote.
+/// The mov muxer does exactly this, back-patching `mdat`/`moov` sizes once
+/// the trailer is known, which is why `write_packet` can't just append;
+/// `crate::dash`'s `ChildIo`/`child_seek` handle an equivalent per-segment
+/// sink the same way.
+pub struct MemoryIo {
+    pub pos: usize,
+    pub output: Vec<u8>,
+}
+
+impl MemoryIo {
+    pub fn for_writing() -> Box<Self> {
+        Box::new(Self { pos: 0, output: Vec::new() })
+    }
+}
+
+unsafe extern "C" fn write_packet(opaque: *mut c_void, buf: *mut u8, buf_size: c_int) -> c_int {
+    let io = &mut *(opaque as *mut MemoryIo);
+    let data = std::slice::from_raw_parts(buf, buf_size as usize);
+    let end = io.pos + data.len();
+    if end > io.output.len() {
+        io.output.resize(end, 0);
+    }
+    io.output[io.pos..end].copy_from_slice(data);
+    io.pos = end;
+    buf_size
+}
+
+unsafe extern "C" fn seek(opaque: *mut c_void, offset: i64, whence: c_int) -> i64 {
+    let io = &mut *(opaque as *mut MemoryIo);
+    let len = io.output.len();
+    match whence {
+        0 /* SEEK_SET */ => io.pos = offset.max(0) as usize,
+        1 /* SEEK_CUR */ => io.pos = (io.pos as i64 + offset).max(0) as usize,
+        2 /* SEEK_END */ => io.pos = (len as i64 + offset).max(0) as usize,
+        AVSEEK_SIZE => return len as i64,
+        _ => return -1,
+    }
+    io.pos as i64
+}
+
+/// Owns the `AVIOContext` and the `av_malloc`'d buffer backing it, plus the
+/// boxed opaque payload its callbacks read/write through. Dropping this frees
+/// both the buffer and the context, mirroring the cleanup `ffmpeg` does
+/// internally for file-backed IO.
+pub struct AvioContext<T> {
+    pub ctx: *mut AVIOContext,
+    io: *mut T,
+    _owner: Box<T>,
+}
+
+impl<T> AvioContext<T> {
+    /// # Safety
+    /// `read_cb`/`write_cb`/`seek_cb` must expect `T` as their opaque
+    /// pointee. The resulting `ctx` must not be read after this value drops.
+    pub unsafe fn with_callbacks(
+        mut owner: Box<T>,
+        writable: bool,
+        read_cb: Option<ReadCb>,
+        write_cb: Option<WriteCb>,
+        seek_cb: Option<SeekCb>,
+    ) -> Self {
+        let buffer = av_malloc(AVIO_BUFFER_SIZE) as *mut u8;
+        let io: *mut T = owner.as_mut();
+        let ctx = avio_alloc_context(
+            buffer,
+            AVIO_BUFFER_SIZE as c_int,
+            writable as c_int,
+            io as *mut c_void,
+            read_cb,
+            write_cb,
+            seek_cb,
+        );
+        Self { ctx, io, _owner: owner }
+    }
+}
+
+impl AvioContext<MemoryIo> {
+    /// # Safety
+    /// Same requirement as [`AvioContext::with_callbacks`].
+    pub unsafe fn new(owner: Box<MemoryIo>) -> Self {
+        Self::with_callbacks(owner, true, None, Some(write_packet), Some(seek))
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        unsafe { std::mem::take(&mut (*self.io).output) }
+    }
+}
+
+impl<T> Drop for AvioContext<T> {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.ctx.is_null() {
+                av_free((*self.ctx).buffer as *mut c_void);
+                avio_context_free(&mut self.ctx);
+            }
+        }
+    }
+}
@@ -0,0 +1,481 @@
+//! Demux/mux wrappers built on top of [`crate::avio`]. `split_streams`
+//! replaces the two `ffmpeg` subprocess calls in `separate_media` with a
+//! single demux pass that routes packets straight into an audio muxer and a
+//! video muxer, both backed by in-memory AVIO.
+
+use crate::avio::{AvioContext, MemoryIo};
+use crate::stream_io::ChannelIo;
+use ffmpeg_sys_next::*;
+use std::os::raw::c_int;
+use std::ptr;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::Receiver;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Holds whichever `AvioContext<T>` is backing a [`Demuxer`]'s `pb` (if any),
+/// so the file-backed and live-streaming constructors can share one struct
+/// and one `Drop` impl despite using different opaque payload types.
+enum DemuxerIo {
+    /// libav owns the IO itself via its native file protocol; no custom pb.
+    NativeFile,
+    Channel(AvioContext<ChannelIo>),
+}
+
+/// Opens a demuxer over a real file or a live channel of incoming chunks,
+/// and owns the underlying `AVFormatContext` (closed on `Drop`) once stream
+/// info has been probed. Shared with [`crate::dash`] and [`crate::live`].
+pub(crate) struct Demuxer {
+    pub(crate) fmt_ctx: *mut AVFormatContext,
+    _avio: DemuxerIo,
+}
+
+impl Demuxer {
+    /// Opens `path` directly through libav's own file protocol — no custom
+    /// AVIO, no whole-upload buffer in RAM. Used for request bodies, which
+    /// are streamed straight to a temp file as they arrive (see
+    /// `crate::upload`) precisely so this is the only place they get read.
+    pub(crate) unsafe fn open_file(path: &std::path::Path) -> Result<Self> {
+        let path_c = std::ffi::CString::new(path.to_string_lossy().as_bytes())?;
+        let mut fmt_ctx = ptr::null_mut();
+        let ret = avformat_open_input(&mut fmt_ctx, path_c.as_ptr(), ptr::null(), ptr::null_mut());
+        if ret < 0 {
+            return Err(format!("avformat_open_input failed: {ret}").into());
+        }
+        let ret = avformat_find_stream_info(fmt_ctx, ptr::null_mut());
+        if ret < 0 {
+            avformat_close_input(&mut fmt_ctx);
+            return Err(format!("avformat_find_stream_info failed: {ret}").into());
+        }
+        Ok(Self { fmt_ctx, _avio: DemuxerIo::NativeFile })
+    }
+
+    /// Opens a demuxer fed by `rx`, explicitly as `mpegts` (SRT carries raw
+    /// MPEG-TS, and a live source can't be probed the way a whole file can).
+    pub(crate) unsafe fn open_streaming(handle: Handle, rx: Receiver<Vec<u8>>) -> Result<Self> {
+        let avio = AvioContext::with_callbacks(
+            ChannelIo::new(handle, rx),
+            false,
+            Some(crate::stream_io::read_packet),
+            None,
+            None,
+        );
+        let format_name = std::ffi::CString::new("mpegts")?;
+        let input_format = av_find_input_format(format_name.as_ptr()) as *const AVInputFormat;
+        if input_format.is_null() {
+            return Err("mpegts demuxer not available in this libav build".into());
+        }
+        let fmt_ctx = Self::open_input(avio.ctx, input_format)?;
+        Ok(Self { fmt_ctx, _avio: DemuxerIo::Channel(avio) })
+    }
+
+    unsafe fn open_input(pb: *mut AVIOContext, input_format: *const AVInputFormat) -> Result<*mut AVFormatContext> {
+        let mut fmt_ctx = avformat_alloc_context();
+        if fmt_ctx.is_null() {
+            return Err("avformat_alloc_context failed".into());
+        }
+        (*fmt_ctx).pb = pb;
+
+        let ret = avformat_open_input(&mut fmt_ctx, ptr::null(), input_format, ptr::null_mut());
+        if ret < 0 {
+            avformat_free_context(fmt_ctx);
+            return Err(format!("avformat_open_input failed: {ret}").into());
+        }
+
+        let ret = avformat_find_stream_info(fmt_ctx, ptr::null_mut());
+        if ret < 0 {
+            avformat_close_input(&mut fmt_ctx);
+            return Err(format!("avformat_find_stream_info failed: {ret}").into());
+        }
+
+        Ok(fmt_ctx)
+    }
+
+    pub(crate) fn stream_index(&self, media_type: AVMediaType) -> Option<usize> {
+        unsafe {
+            let streams =
+                std::slice::from_raw_parts((*self.fmt_ctx).streams, (*self.fmt_ctx).nb_streams as usize);
+            streams
+                .iter()
+                .position(|&s| (*(*s).codecpar).codec_type == media_type)
+        }
+    }
+}
+
+impl Drop for Demuxer {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.fmt_ctx.is_null() {
+                avformat_close_input(&mut self.fmt_ctx);
+            }
+        }
+    }
+}
+
+/// A single-stream output container (`.aac` or `.mp4`) that copies packets
+/// without decode/encode, writing into an in-memory AVIO buffer.
+struct Muxer {
+    fmt_ctx: *mut AVFormatContext,
+    avio: AvioContext<MemoryIo>,
+    in_stream_index: usize,
+    out_stream_index: c_int,
+    started: bool,
+}
+
+impl Muxer {
+    unsafe fn new(format_name: &str, demuxer: &Demuxer, in_stream_index: usize) -> Result<Self> {
+        let format_name_c = std::ffi::CString::new(format_name)?;
+        let mut fmt_ctx = ptr::null_mut();
+        let ret = avformat_alloc_output_context2(
+            &mut fmt_ctx,
+            ptr::null(),
+            format_name_c.as_ptr(),
+            ptr::null(),
+        );
+        if ret < 0 || fmt_ctx.is_null() {
+            return Err(format!("avformat_alloc_output_context2 failed: {ret}").into());
+        }
+
+        let in_stream = *(*demuxer.fmt_ctx).streams.add(in_stream_index);
+        let out_stream = avformat_new_stream(fmt_ctx, ptr::null());
+        if out_stream.is_null() {
+            avformat_free_context(fmt_ctx);
+            return Err("avformat_new_stream failed".into());
+        }
+        avcodec_parameters_copy((*out_stream).codecpar, (*in_stream).codecpar);
+        (*(*out_stream).codecpar).codec_tag = 0;
+
+        let avio = AvioContext::new(MemoryIo::for_writing());
+        (*fmt_ctx).pb = avio.ctx;
+        (*fmt_ctx).flags |= AVFMT_FLAG_CUSTOM_IO as c_int;
+
+        Ok(Self {
+            fmt_ctx,
+            avio,
+            in_stream_index,
+            out_stream_index: 0,
+            started: false,
+        })
+    }
+
+    unsafe fn ensure_header(&mut self) -> Result<()> {
+        if !self.started {
+            let ret = avformat_write_header(self.fmt_ctx, ptr::null_mut());
+            if ret < 0 {
+                return Err(format!("avformat_write_header failed: {ret}").into());
+            }
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    unsafe fn write_packet(&mut self, in_stream: *mut AVStream, pkt: *mut AVPacket) -> Result<()> {
+        self.ensure_header()?;
+        let out_stream = *(*self.fmt_ctx).streams.add(self.out_stream_index as usize);
+        av_packet_rescale_ts(pkt, (*in_stream).time_base, (*out_stream).time_base);
+        (*pkt).stream_index = self.out_stream_index;
+        let ret = av_interleaved_write_frame(self.fmt_ctx, pkt);
+        if ret < 0 {
+            return Err(format!("av_interleaved_write_frame failed: {ret}").into());
+        }
+        Ok(())
+    }
+
+    unsafe fn finish(mut self) -> Result<Vec<u8>> {
+        if self.started {
+            let ret = av_write_trailer(self.fmt_ctx);
+            if ret < 0 {
+                return Err(format!("av_write_trailer failed: {ret}").into());
+            }
+        }
+        Ok(self.avio.take_output())
+    }
+}
+
+impl Drop for Muxer {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.fmt_ctx.is_null() {
+                avformat_free_context(self.fmt_ctx);
+            }
+        }
+    }
+}
+
+/// Demuxes the upload at `input_path` once and routes packets straight into
+/// an audio muxer (`adts`, i.e. raw `.aac`) and a video muxer (`mp4`), both
+/// stream-copied with no decode/encode. Returns `(audio_bytes, video_bytes)`.
+pub fn split_streams(input_path: &std::path::Path) -> Result<(Vec<u8>, Vec<u8>)> {
+    unsafe {
+        let demuxer = Demuxer::open_file(input_path)?;
+        let audio_index = demuxer
+            .stream_index(AVMediaType::AVMEDIA_TYPE_AUDIO)
+            .ok_or("input has no audio stream")?;
+        let video_index = demuxer
+            .stream_index(AVMediaType::AVMEDIA_TYPE_VIDEO)
+            .ok_or("input has no video stream")?;
+
+        let mut audio_muxer = Muxer::new("adts", &demuxer, audio_index)?;
+        let mut video_muxer = Muxer::new("mp4", &demuxer, video_index)?;
+
+        let mut pkt = av_packet_alloc();
+        if pkt.is_null() {
+            return Err("av_packet_alloc failed".into());
+        }
+
+        loop {
+            let ret = av_read_frame(demuxer.fmt_ctx, pkt);
+            if ret == AVERROR_EOF {
+                break;
+            }
+            if ret < 0 {
+                av_packet_free(&mut pkt);
+                return Err(format!("av_read_frame failed: {ret}").into());
+            }
+
+            let in_index = (*pkt).stream_index as usize;
+            let in_stream = *(*demuxer.fmt_ctx).streams.add(in_index);
+            if in_index == audio_index {
+                audio_muxer.write_packet(in_stream, pkt)?;
+            } else if in_index == video_index {
+                video_muxer.write_packet(in_stream, pkt)?;
+            }
+            av_packet_unref(pkt);
+        }
+        av_packet_free(&mut pkt);
+
+        let audio_data = audio_muxer.finish()?;
+        let video_data = video_muxer.finish()?;
+        Ok((audio_data, video_data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::avio::{AvioContext, MemoryIo};
+
+    const AUDIO_SAMPLE_RATE: i32 = 8_000;
+    const VIDEO_SIZE: i32 = 16;
+    const FRAME_COUNT: i32 = 3;
+
+    unsafe fn encode_silent_aac() -> Result<(*mut AVCodecContext, Vec<(i64, Vec<u8>)>)> {
+        let codec = avcodec_find_encoder(AVCodecID::AV_CODEC_ID_AAC);
+        if codec.is_null() {
+            return Err("no AAC encoder built into this libav".into());
+        }
+        let ctx = avcodec_alloc_context3(codec);
+        (*ctx).sample_fmt = AVSampleFormat::AV_SAMPLE_FMT_FLTP;
+        (*ctx).sample_rate = AUDIO_SAMPLE_RATE;
+        (*ctx).channel_layout = AV_CH_LAYOUT_MONO;
+        (*ctx).channels = 1;
+        (*ctx).bit_rate = 64_000;
+        (*ctx).time_base = AVRational { num: 1, den: AUDIO_SAMPLE_RATE };
+        if avcodec_open2(ctx, codec, ptr::null_mut()) < 0 {
+            return Err("avcodec_open2 (aac encoder) failed".into());
+        }
+
+        let mut frame = av_frame_alloc();
+        (*frame).nb_samples = (*ctx).frame_size;
+        (*frame).format = AVSampleFormat::AV_SAMPLE_FMT_FLTP as c_int;
+        (*frame).channel_layout = (*ctx).channel_layout;
+        (*frame).channels = (*ctx).channels;
+        (*frame).sample_rate = (*ctx).sample_rate;
+        if av_frame_get_buffer(frame, 0) < 0 {
+            return Err("av_frame_get_buffer (audio) failed".into());
+        }
+
+        let mut packets = Vec::new();
+        let mut pkt = av_packet_alloc();
+        for i in 0..=FRAME_COUNT {
+            let sending_frame = i < FRAME_COUNT;
+            if sending_frame {
+                av_samples_set_silence(
+                    (*frame).extended_data,
+                    0,
+                    (*frame).nb_samples,
+                    (*frame).channels,
+                    AVSampleFormat::AV_SAMPLE_FMT_FLTP,
+                );
+                (*frame).pts = (i as i64) * (*ctx).frame_size as i64;
+                avcodec_send_frame(ctx, frame);
+            } else {
+                avcodec_send_frame(ctx, ptr::null()); // flush
+            }
+            loop {
+                let ret = avcodec_receive_packet(ctx, pkt);
+                if ret == AVERROR(EAGAIN) || ret == AVERROR_EOF {
+                    break;
+                }
+                if ret < 0 {
+                    return Err(format!("avcodec_receive_packet (aac) failed: {ret}").into());
+                }
+                let data = std::slice::from_raw_parts((*pkt).data, (*pkt).size as usize).to_vec();
+                packets.push(((*pkt).pts, data));
+                av_packet_unref(pkt);
+            }
+        }
+        av_packet_free(&mut pkt);
+        av_frame_free(&mut frame);
+        Ok((ctx, packets))
+    }
+
+    unsafe fn encode_blank_mjpeg() -> Result<(*mut AVCodecContext, Vec<(i64, Vec<u8>)>)> {
+        let codec = avcodec_find_encoder(AVCodecID::AV_CODEC_ID_MJPEG);
+        if codec.is_null() {
+            return Err("no MJPEG encoder built into this libav".into());
+        }
+        let ctx = avcodec_alloc_context3(codec);
+        (*ctx).width = VIDEO_SIZE;
+        (*ctx).height = VIDEO_SIZE;
+        (*ctx).pix_fmt = AVPixelFormat::AV_PIX_FMT_YUVJ420P;
+        (*ctx).time_base = AVRational { num: 1, den: 1 };
+        if avcodec_open2(ctx, codec, ptr::null_mut()) < 0 {
+            return Err("avcodec_open2 (mjpeg encoder) failed".into());
+        }
+
+        let mut frame = av_frame_alloc();
+        (*frame).width = VIDEO_SIZE;
+        (*frame).height = VIDEO_SIZE;
+        (*frame).format = AVPixelFormat::AV_PIX_FMT_YUVJ420P as c_int;
+        if av_frame_get_buffer(frame, 0) < 0 {
+            return Err("av_frame_get_buffer (video) failed".into());
+        }
+
+        let mut packets = Vec::new();
+        let mut pkt = av_packet_alloc();
+        for i in 0..=FRAME_COUNT {
+            let sending_frame = i < FRAME_COUNT;
+            if sending_frame {
+                for plane in 0..3 {
+                    let plane_height = if plane == 0 { VIDEO_SIZE } else { VIDEO_SIZE / 2 };
+                    let linesize = (*frame).linesize[plane] as usize;
+                    if !(*frame).data[plane].is_null() {
+                        ptr::write_bytes((*frame).data[plane], 128u8, linesize * plane_height as usize);
+                    }
+                }
+                (*frame).pts = i as i64;
+                avcodec_send_frame(ctx, frame);
+            } else {
+                avcodec_send_frame(ctx, ptr::null()); // flush
+            }
+            loop {
+                let ret = avcodec_receive_packet(ctx, pkt);
+                if ret == AVERROR(EAGAIN) || ret == AVERROR_EOF {
+                    break;
+                }
+                if ret < 0 {
+                    return Err(format!("avcodec_receive_packet (mjpeg) failed: {ret}").into());
+                }
+                let data = std::slice::from_raw_parts((*pkt).data, (*pkt).size as usize).to_vec();
+                packets.push(((*pkt).pts, data));
+                av_packet_unref(pkt);
+            }
+        }
+        av_packet_free(&mut pkt);
+        av_frame_free(&mut frame);
+        Ok((ctx, packets))
+    }
+
+    /// Builds a tiny but real mp4 file with one silent AAC audio stream and
+    /// one blank MJPEG video stream, muxed through the same `MemoryIo`/
+    /// `AvioContext` plumbing `split_streams`'s own `Muxer` uses — so this
+    /// fixture doubles as a sanity check that the `avio` write/seek fix
+    /// (the mov muxer's back-patched `mdat`/`moov` sizes) produces a file
+    /// `avformat_open_input` can actually read back.
+    unsafe fn build_fixture_mp4() -> Result<Vec<u8>> {
+        let (audio_ctx, audio_pkts) = encode_silent_aac()?;
+        let (video_ctx, video_pkts) = encode_blank_mjpeg()?;
+
+        let format_name = std::ffi::CString::new("mp4")?;
+        let mut fmt_ctx = ptr::null_mut();
+        let ret = avformat_alloc_output_context2(&mut fmt_ctx, ptr::null(), format_name.as_ptr(), ptr::null());
+        if ret < 0 || fmt_ctx.is_null() {
+            return Err(format!("avformat_alloc_output_context2 failed: {ret}").into());
+        }
+
+        let audio_stream = avformat_new_stream(fmt_ctx, ptr::null());
+        avcodec_parameters_from_context((*audio_stream).codecpar, audio_ctx);
+        let video_stream = avformat_new_stream(fmt_ctx, ptr::null());
+        avcodec_parameters_from_context((*video_stream).codecpar, video_ctx);
+
+        let avio = AvioContext::new(MemoryIo::for_writing());
+        (*fmt_ctx).pb = avio.ctx;
+        (*fmt_ctx).flags |= AVFMT_FLAG_CUSTOM_IO as c_int;
+
+        let ret = avformat_write_header(fmt_ctx, ptr::null_mut());
+        if ret < 0 {
+            avformat_free_context(fmt_ctx);
+            return Err(format!("avformat_write_header (fixture) failed: {ret}").into());
+        }
+
+        let mut pkt = av_packet_alloc();
+        for (stream, in_time_base, packets) in [
+            (audio_stream, (*audio_ctx).time_base, audio_pkts),
+            (video_stream, (*video_ctx).time_base, video_pkts),
+        ] {
+            for (pts, data) in packets {
+                av_new_packet(pkt, data.len() as c_int);
+                std::ptr::copy_nonoverlapping(data.as_ptr(), (*pkt).data, data.len());
+                (*pkt).pts = pts;
+                (*pkt).dts = pts;
+                (*pkt).stream_index = (*stream).index;
+                av_packet_rescale_ts(pkt, in_time_base, (*stream).time_base);
+                let ret = av_interleaved_write_frame(fmt_ctx, pkt);
+                av_packet_unref(pkt);
+                if ret < 0 {
+                    return Err(format!("av_interleaved_write_frame (fixture) failed: {ret}").into());
+                }
+            }
+        }
+        av_packet_free(&mut pkt);
+
+        let ret = av_write_trailer(fmt_ctx);
+        if ret < 0 {
+            return Err(format!("av_write_trailer (fixture) failed: {ret}").into());
+        }
+
+        let mut avio = avio;
+        let bytes = avio.take_output();
+        avformat_free_context(fmt_ctx);
+        let mut audio_ctx = audio_ctx;
+        let mut video_ctx = video_ctx;
+        avcodec_free_context(&mut audio_ctx);
+        avcodec_free_context(&mut video_ctx);
+
+        Ok(bytes)
+    }
+
+    #[test]
+    fn split_streams_round_trips_through_a_real_demux_mux_pass() {
+        let input = unsafe { build_fixture_mp4() }.expect("building the fixture mp4 failed");
+
+        let input_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(input_file.path(), &input).unwrap();
+
+        let (audio_data, video_data) =
+            split_streams(input_file.path()).expect("split_streams failed on the fixture");
+        assert!(!audio_data.is_empty(), "split_streams produced no audio bytes");
+        assert!(!video_data.is_empty(), "split_streams produced no video bytes");
+
+        let audio_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(audio_file.path(), &audio_data).unwrap();
+        let audio_demuxer = unsafe { Demuxer::open_file(audio_file.path()) }
+            .expect("the split-out .aac is not a valid, demuxable file");
+        assert_eq!(
+            audio_demuxer.stream_index(AVMediaType::AVMEDIA_TYPE_AUDIO),
+            Some(0),
+            "split-out .aac should demux back with its audio as stream 0"
+        );
+
+        let video_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(video_file.path(), &video_data).unwrap();
+        let video_demuxer = unsafe { Demuxer::open_file(video_file.path()) }
+            .expect("the split-out .mp4 is not a valid, demuxable file — this is the mov back-patch bug");
+        assert_eq!(
+            video_demuxer.stream_index(AVMediaType::AVMEDIA_TYPE_VIDEO),
+            Some(0),
+            "split-out .mp4 should demux back with its video as stream 0"
+        );
+    }
+}
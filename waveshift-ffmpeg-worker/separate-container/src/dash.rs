@@ -0,0 +1,249 @@
+//! DASH/HLS packaging, shared by the one-shot `/segment` endpoint and the
+//! continuous live-ingest path in `crate::live`. Drives a single `dash`
+//! muxer over an already-open [`Demuxer`] with `io_open`/`io_close`
+//! overridden so every sub-file the muxer asks for (the `.mpd`, per-track
+//! init segments, numbered media segments, and — with `hls_playlist=1` —
+//! the `.m3u8` variants) is handed to a caller-supplied sink instead of
+//! landing on disk. The muxer closes each finished segment file as soon as
+//! the next one starts, so the sink sees files as they're produced, not just
+//! at the very end — which is what makes this usable for a live source that
+//! never reaches "the end".
+
+use crate::container::Demuxer;
+use ffmpeg_sys_next::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::rc::Rc;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+const AVIO_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Called once per finished sub-file, with its muxer-assigned filename.
+type Sink = dyn FnMut(String, Vec<u8>);
+
+struct ChildIo {
+    name: String,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+unsafe extern "C" fn child_write(opaque: *mut c_void, buf: *mut u8, buf_size: c_int) -> c_int {
+    let child = &mut *(opaque as *mut ChildIo);
+    let data = std::slice::from_raw_parts(buf, buf_size as usize);
+    let end = child.pos + data.len();
+    if end > child.buf.len() {
+        child.buf.resize(end, 0);
+    }
+    child.buf[child.pos..end].copy_from_slice(data);
+    child.pos = end;
+    buf_size
+}
+
+unsafe extern "C" fn child_seek(opaque: *mut c_void, offset: i64, whence: c_int) -> i64 {
+    let child = &mut *(opaque as *mut ChildIo);
+    match whence {
+        0 /* SEEK_SET */ => child.pos = offset.max(0) as usize,
+        1 /* SEEK_CUR */ => child.pos = (child.pos as i64 + offset).max(0) as usize,
+        2 /* SEEK_END */ => child.pos = (child.buf.len() as i64 + offset).max(0) as usize,
+        AVSEEK_SIZE => return child.buf.len() as i64,
+        _ => return -1,
+    }
+    child.pos as i64
+}
+
+unsafe extern "C" fn io_open(
+    _s: *mut AVFormatContext,
+    pb: *mut *mut AVIOContext,
+    url: *const c_char,
+    _flags: c_int,
+    _options: *mut *mut AVDictionary,
+) -> c_int {
+    let name = CStr::from_ptr(url).to_string_lossy().into_owned();
+    let child = Box::new(ChildIo { name, buf: Vec::new(), pos: 0 });
+    let child_ptr = Box::into_raw(child);
+
+    let buffer = av_malloc(AVIO_BUFFER_SIZE) as *mut u8;
+    let ctx = avio_alloc_context(
+        buffer,
+        AVIO_BUFFER_SIZE as c_int,
+        1,
+        child_ptr as *mut c_void,
+        None,
+        Some(child_write),
+        Some(child_seek),
+    );
+    if ctx.is_null() {
+        drop(Box::from_raw(child_ptr));
+        return AVERROR(ENOMEM);
+    }
+    *pb = ctx;
+    0
+}
+
+/// Reads the sink back out of `s.opaque` (stashed there by the caller as a
+/// `Box<Box<Sink>>`, so the outer raw pointer stays thin) and hands it the
+/// file that just closed.
+unsafe extern "C" fn io_close(s: *mut AVFormatContext, mut pb: *mut AVIOContext) {
+    if pb.is_null() {
+        return;
+    }
+    avio_flush(pb);
+    let child = Box::from_raw((*pb).opaque as *mut ChildIo);
+    let sink = &mut *((*s).opaque as *mut Box<Sink>);
+    sink(child.name, child.buf);
+
+    av_free((*pb).buffer as *mut c_void);
+    avio_context_free(&mut pb);
+}
+
+unsafe fn set_opt(opts: &mut *mut AVDictionary, key: &str, value: &str) {
+    let key = CString::new(key).unwrap();
+    let value = CString::new(value).unwrap();
+    av_dict_set(opts, key.as_ptr(), value.as_ptr(), 0);
+}
+
+/// Drives a `dash` muxer over `demuxer` until its input is exhausted (or
+/// errors), calling `sink` with every finished sub-file. Blocks for as long
+/// as the demuxer keeps producing packets — for a whole-file `Demuxer` that's
+/// until EOF; for a live one, until the publisher disconnects.
+///
+/// `window_size` is the MPD's `<SegmentTemplate>` window: `0` keeps every
+/// segment ever produced (what a finite VOD file needs, so playback can
+/// start from the beginning), while a live source passes a small positive
+/// value to keep the manifest — and its own memory use — bounded.
+pub(crate) fn run_dash_muxer(
+    demuxer: Demuxer,
+    segment_duration_secs: u32,
+    window_size: u32,
+    mut sink: Box<Sink>,
+) -> Result<()> {
+    unsafe {
+        let audio_index = demuxer
+            .stream_index(AVMediaType::AVMEDIA_TYPE_AUDIO)
+            .ok_or("input has no audio stream")?;
+        let video_index = demuxer
+            .stream_index(AVMediaType::AVMEDIA_TYPE_VIDEO)
+            .ok_or("input has no video stream")?;
+
+        let mut fmt_ctx = ptr::null_mut();
+        let format_name = CString::new("dash")?;
+        let out_name = CString::new("dash.mpd")?;
+        let ret = avformat_alloc_output_context2(
+            &mut fmt_ctx,
+            ptr::null_mut(),
+            format_name.as_ptr(),
+            out_name.as_ptr(),
+        );
+        if ret < 0 || fmt_ctx.is_null() {
+            return Err(format!("avformat_alloc_output_context2 failed: {ret}").into());
+        }
+
+        // 顺序固定为 [video, audio]，跟 adaptation_sets 里 "id=0,streams=v id=1,streams=a" 对应
+        let mut out_index = HashMap::new();
+        for (slot, in_index) in [video_index, audio_index].into_iter().enumerate() {
+            let in_stream = *(*demuxer.fmt_ctx).streams.add(in_index);
+            let out_stream = avformat_new_stream(fmt_ctx, ptr::null());
+            if out_stream.is_null() {
+                avformat_free_context(fmt_ctx);
+                return Err("avformat_new_stream failed".into());
+            }
+            avcodec_parameters_copy((*out_stream).codecpar, (*in_stream).codecpar);
+            (*(*out_stream).codecpar).codec_tag = 0;
+            out_index.insert(in_index, slot as c_int);
+        }
+
+        let boxed_sink: Box<Box<Sink>> = Box::new(sink);
+        let sink_ptr = Box::into_raw(boxed_sink);
+        (*fmt_ctx).opaque = sink_ptr as *mut c_void;
+        (*fmt_ctx).io_open = Some(io_open);
+        (*fmt_ctx).io_close = Some(io_close);
+        (*fmt_ctx).flags |= AVFMT_FLAG_CUSTOM_IO as c_int;
+
+        let mut opts: *mut AVDictionary = ptr::null_mut();
+        set_opt(&mut opts, "use_timeline", "1");
+        set_opt(&mut opts, "use_template", "1");
+        set_opt(&mut opts, "hls_playlist", "1");
+        set_opt(&mut opts, "seg_duration", &segment_duration_secs.to_string());
+        set_opt(&mut opts, "window_size", &window_size.to_string());
+        set_opt(&mut opts, "adaptation_sets", "id=0,streams=v id=1,streams=a");
+
+        let ret = avformat_write_header(fmt_ctx, &mut opts);
+        av_dict_free(&mut opts);
+        if ret < 0 {
+            avformat_free_context(fmt_ctx);
+            drop(Box::from_raw(sink_ptr));
+            return Err(format!("avformat_write_header (dash) failed: {ret}").into());
+        }
+
+        let mut pkt = av_packet_alloc();
+        let mut read_err = None;
+        loop {
+            let ret = av_read_frame(demuxer.fmt_ctx, pkt);
+            if ret == AVERROR_EOF {
+                break;
+            }
+            if ret < 0 {
+                read_err = Some(format!("av_read_frame failed: {ret}"));
+                break;
+            }
+
+            let in_index = (*pkt).stream_index as usize;
+            if let Some(&slot) = out_index.get(&in_index) {
+                let in_stream = *(*demuxer.fmt_ctx).streams.add(in_index);
+                let out_stream = *(*fmt_ctx).streams.add(slot as usize);
+                av_packet_rescale_ts(pkt, (*in_stream).time_base, (*out_stream).time_base);
+                (*pkt).stream_index = slot;
+                let ret = av_interleaved_write_frame(fmt_ctx, pkt);
+                if ret < 0 {
+                    read_err = Some(format!("av_interleaved_write_frame failed: {ret}"));
+                    av_packet_unref(pkt);
+                    break;
+                }
+            }
+            av_packet_unref(pkt);
+        }
+        av_packet_free(&mut pkt);
+
+        let trailer_ret = av_write_trailer(fmt_ctx);
+        avformat_free_context(fmt_ctx);
+        // `sink` (and the `Box<Box<Sink>>` wrapper) must outlive every
+        // io_close call above, so only reclaim it now that muxing is done
+        sink = *Box::from_raw(sink_ptr);
+        drop(sink);
+
+        if let Some(err) = read_err {
+            return Err(err.into());
+        }
+        if trailer_ret < 0 {
+            return Err(format!("av_write_trailer (dash) failed: {trailer_ret}").into());
+        }
+        Ok(())
+    }
+}
+
+/// Packages the upload at `input_path` as DASH (with an accompanying HLS
+/// playlist) via a single demux pass. `segment_duration_secs` controls
+/// `seg_duration`. This is a finite, one-shot file, not a rolling live
+/// source, so the manifest keeps every segment (`window_size=0`) rather
+/// than windowing down to the most recent few — otherwise a client
+/// couldn't play the start of the video even though every segment came
+/// back in the response. Returns every generated file keyed by filename.
+pub fn package_dash(
+    input_path: &std::path::Path,
+    segment_duration_secs: u32,
+) -> Result<HashMap<String, Vec<u8>>> {
+    let demuxer = unsafe { Demuxer::open_file(input_path)? };
+    let files = Rc::new(RefCell::new(HashMap::new()));
+    let files_out = files.clone();
+    let sink: Box<Sink> = Box::new(move |name, data| {
+        files.borrow_mut().insert(name, data);
+    });
+    run_dash_muxer(demuxer, segment_duration_secs, 0, sink)?;
+    Ok(Rc::try_unwrap(files_out)
+        .map_err(|_| "dash sink outlived the muxer run")?
+        .into_inner())
+}
@@ -0,0 +1,94 @@
+//! Live SRT ingest (optional, off by default).
+//!
+//! Listens for a live MPEG-TS stream over SRT — one publisher at a time —
+//! and feeds it straight into the [`crate::dash`] packager as it arrives,
+//! instead of requiring a whole file POSTed to `/`. Incoming packets are
+//! forwarded through a bounded channel into the demux/mux stage, so memory
+//! stays flat regardless of how long the broadcast runs. Enabled by setting
+//! `SRT_LISTEN_PORT`; `/` and `/segment` work the same whether or not this is
+//! running.
+
+use crate::container::Demuxer;
+use crate::dash;
+use futures::StreamExt;
+use srt_tokio::SrtListener;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+const CHANNEL_CAPACITY: usize = 64;
+const DEFAULT_SEGMENT_DURATION_SECS: u32 = 6;
+/// How many of the most recently produced segment files to keep around.
+/// Bounds memory for a broadcast that runs indefinitely.
+const ROLLING_SEGMENT_HISTORY: usize = 64;
+/// `dash.mpd`'s `<SegmentTemplate>` window, in segments. Unlike the one-shot
+/// `/segment` endpoint (which keeps everything, `window_size=0`), a live
+/// broadcast never ends, so the manifest must window down to a handful of
+/// recent segments or it would grow without bound.
+const DASH_WINDOW_SIZE: u32 = 5;
+
+pub type SegmentRing = Arc<Mutex<VecDeque<(String, Vec<u8>)>>>;
+
+/// Binds `port` and loops forever, accepting one SRT publisher at a time.
+pub async fn run(port: u16, segments: SegmentRing) -> Result<()> {
+    println!("📡 SRT 直播接入已启用，监听 UDP 端口 {}", port);
+    let (_listener, mut incoming) = SrtListener::builder().bind(port).await?;
+
+    while let Some(request) = incoming.incoming().next().await {
+        let mut socket = match request.accept(None).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!("❌ SRT 握手失败: {}", e);
+                continue;
+            }
+        };
+        println!("✅ SRT 发布者已连接");
+
+        let (tx, rx) = mpsc::channel::<Vec<u8>>(CHANNEL_CAPACITY);
+        let handle = Handle::current();
+        let segments = segments.clone();
+        let demux_task =
+            tokio::task::spawn_blocking(move || demux_and_package(handle, rx, segments));
+
+        loop {
+            match socket.next().await {
+                Some(Ok((_instant, packet))) => {
+                    if tx.send(packet.to_vec()).await.is_err() {
+                        break; // 解复用端已经退出
+                    }
+                }
+                Some(Err(e)) => {
+                    eprintln!("❌ SRT 读取失败: {}", e);
+                    break;
+                }
+                None => break, // 发布者断开
+            }
+        }
+        drop(tx);
+
+        match demux_task.await {
+            Ok(Err(e)) => eprintln!("❌ 直播分段任务出错: {}", e),
+            Err(e) => eprintln!("❌ 直播分段任务异常退出: {:?}", e),
+            Ok(Ok(())) => {}
+        }
+        println!("🔌 SRT 发布者已断开，等待下一路连接");
+    }
+
+    Ok(())
+}
+
+fn demux_and_package(handle: Handle, rx: mpsc::Receiver<Vec<u8>>, segments: SegmentRing) -> Result<()> {
+    let demuxer = unsafe { Demuxer::open_streaming(handle, rx)? };
+    let sink: Box<dyn FnMut(String, Vec<u8>)> = Box::new(move |name, data| {
+        println!("🎬 直播分段就绪: {} ({} bytes)", name, data.len());
+        let mut ring = segments.lock().unwrap();
+        ring.push_back((name, data));
+        while ring.len() > ROLLING_SEGMENT_HISTORY {
+            ring.pop_front();
+        }
+    });
+    dash::run_dash_muxer(demuxer, DEFAULT_SEGMENT_DURATION_SECS, DASH_WINDOW_SIZE, sink)
+}
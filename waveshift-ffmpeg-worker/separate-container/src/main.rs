@@ -1,16 +1,23 @@
-use tokio::process::Command;
-use std::io::Write;
-use tempfile::NamedTempFile;
+use bytes::Bytes;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
-use tokio::fs;
-use uuid::Uuid;
+
+mod avio;
+mod container;
+mod dash;
+mod live;
+mod stream_io;
+mod upload;
+
+const DEFAULT_SEGMENT_DURATION_SECS: u32 = 6;
+const MULTIPART_BOUNDARY: &str = "----formdata-boundary-1234567890";
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 async fn handle_request(req: Request<Body>) -> Result<Response<Body>> {
     match (req.method(), req.uri().path()) {
         (&Method::POST, "/") => separate_media(req).await,
+        (&Method::POST, "/segment") => segment_media(req).await,
         (&Method::GET, "/health") => Ok(Response::new(Body::from("OK"))),
         _ => {
             let mut not_found = Response::default();
@@ -20,146 +27,123 @@ async fn handle_request(req: Request<Body>) -> Result<Response<Body>> {
     }
 }
 
+/// Builds a streaming `multipart/form-data` response out of `(field name,
+/// filename, content type, bytes)` parts: each part's header block and each
+/// file's bytes is handed to `Body::wrap_stream` as its own chunk, so nothing gets
+/// copied into one giant concatenated `Vec` before the response can start
+/// sending. Shared between `/` and `/segment`.
+fn multipart_response(parts: Vec<(String, String, String, Vec<u8>)>) -> Result<Response<Body>> {
+    let boundary = MULTIPART_BOUNDARY;
+    let mut chunks: Vec<std::result::Result<Bytes, std::io::Error>> = Vec::with_capacity(parts.len() * 3 + 1);
+
+    for (name, filename, content_type, data) in parts {
+        chunks.push(Ok(Bytes::from(format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"; filename=\"{filename}\"\r\nContent-Type: {content_type}\r\n\r\n"
+        ))));
+        chunks.push(Ok(Bytes::from(data)));
+        chunks.push(Ok(Bytes::from_static(b"\r\n")));
+    }
+    chunks.push(Ok(Bytes::from(format!("--{boundary}--\r\n"))));
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
+        .body(Body::wrap_stream(futures::stream::iter(chunks)))?)
+}
+
 async fn separate_media(req: Request<Body>) -> Result<Response<Body>> {
     println!("开始处理音视频分离请求");
-    
-    // 获取请求体
-    let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
-    println!("接收文件大小: {} bytes", body_bytes.len());
-    
-    // 创建临时文件来保存上传的视频
-    let mut input_file = NamedTempFile::new()?;
-    input_file.write_all(&body_bytes)?;
-    let input_path = input_file.path();
-    
-    // 生成唯一的文件名 - 使用相对路径更安全
-    let uuid = Uuid::new_v4();
-    let audio_filename = format!("{}_audio.aac", uuid);
-    let video_filename = format!("{}_video.mp4", uuid);
-    
-    println!("输入文件: {:?}", input_path);
-    println!("音频输出: {}", audio_filename);
-    println!("视频输出: {}", video_filename);
-    
-    println!("开始并行音视频分离...");
-    
-    // 🔥 关键优化：并行执行音频和视频分离
-    let audio_future = Command::new("ffmpeg")
-        .args(&[
-            "-i", input_path.to_str().unwrap(),
-            "-vn",      // 不包含视频
-            "-c:a", "copy",  // 复制音频流
-            "-y",       // 覆盖输出文件
-            &audio_filename
-        ])
-        .output();
-
-    let video_future = Command::new("ffmpeg")
-        .args(&[
-            "-i", input_path.to_str().unwrap(),
-            "-an",      // 不包含音频
-            "-c:v", "copy",  // 复制视频流
-            "-y",       // 覆盖输出文件
-            &video_filename
-        ])
-        .output();
-
-    // 等待两个任务同时完成 - 这是关键性能提升点
-    let (audio_result, video_result) = tokio::try_join!(audio_future, video_future)?;
-    
-    // 检查音频分离结果
-    if !audio_result.status.success() {
-        let error_msg = String::from_utf8_lossy(&audio_result.stderr);
-        eprintln!("音频分离失败: {}", error_msg);
-        return Err(format!("音频分离失败: {}", error_msg).into());
-    }
-    
-    // 检查视频分离结果
-    if !video_result.status.success() {
-        let error_msg = String::from_utf8_lossy(&video_result.stderr);
-        eprintln!("视频分离失败: {}", error_msg);
-        return Err(format!("视频分离失败: {}", error_msg).into());
-    }
-    
-    println!("并行处理完成！");
-    
-    println!("FFMPEG 处理完成");
-    
-    // 检查输出文件是否存在
-    if !std::path::Path::new(&audio_filename).exists() {
-        return Err(format!("音频输出文件不存在: {}", audio_filename).into());
-    }
-    if !std::path::Path::new(&video_filename).exists() {
-        return Err(format!("视频输出文件不存在: {}", video_filename).into());
-    }
-    
-    // 读取输出文件
-    let audio_data = fs::read(&audio_filename).await?;
-    let video_data = fs::read(&video_filename).await?;
-    
+
+    // 流式写入临时文件，峰值内存只取决于单个 chunk，而不是整个上传大小
+    let (input_file, received) = upload::stream_to_tempfile(req).await?;
+    println!("接收文件大小: {} bytes", received);
+
+    // 单次 demux，直接路由到音频/视频两个内存 Muxer，无需子进程
+    let input_path = input_file.path().to_path_buf();
+    let (audio_data, video_data) =
+        tokio::task::spawn_blocking(move || container::split_streams(&input_path)).await??;
+    drop(input_file); // 分离完成后再清理临时文件
+
     println!("音频大小: {} bytes", audio_data.len());
     println!("视频大小: {} bytes", video_data.len());
-    
-    // 清理临时文件
-    let _ = fs::remove_file(&audio_filename).await;
-    let _ = fs::remove_file(&video_filename).await;
-    
-    // 构建 multipart 响应
-    let boundary = "----formdata-boundary-1234567890";
-    let mut response_body = Vec::new();
-    
-    // 添加音频文件
-    response_body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
-    response_body.extend_from_slice(b"Content-Disposition: form-data; name=\"audio\"; filename=\"audio.aac\"\r\n");
-    response_body.extend_from_slice(b"Content-Type: audio/aac\r\n\r\n");
-    response_body.extend_from_slice(&audio_data);
-    response_body.extend_from_slice(b"\r\n");
-    
-    // 添加视频文件
-    response_body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
-    response_body.extend_from_slice(b"Content-Disposition: form-data; name=\"video\"; filename=\"video.mp4\"\r\n");
-    response_body.extend_from_slice(b"Content-Type: video/mp4\r\n\r\n");
-    response_body.extend_from_slice(&video_data);
-    response_body.extend_from_slice(b"\r\n");
-    
-    // 结束边界
-    response_body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
-    
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", format!("multipart/form-data; boundary={}", boundary))
-        .body(Body::from(response_body))?;
-    
-    println!("并行处理响应发送完成");
+
+    let response = multipart_response(vec![
+        ("audio".into(), "audio.aac".into(), "audio/aac".into(), audio_data),
+        ("video".into(), "video.mp4".into(), "video/mp4".into(), video_data),
+    ])?;
+
+    println!("响应发送完成");
+    Ok(response)
+}
+
+/// `POST /segment` — same input as `/`, but packages the demuxed audio/video
+/// as DASH (+ HLS) segments plus a manifest instead of two whole files.
+async fn segment_media(req: Request<Body>) -> Result<Response<Body>> {
+    let segment_duration_secs = req
+        .headers()
+        .get("x-segment-duration")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_SEGMENT_DURATION_SECS);
+
+    println!("开始处理分段打包请求 (seg_duration={}s)", segment_duration_secs);
+
+    let (input_file, received) = upload::stream_to_tempfile(req).await?;
+    println!("接收文件大小: {} bytes", received);
+
+    let input_path = input_file.path().to_path_buf();
+    let files = tokio::task::spawn_blocking(move || {
+        dash::package_dash(&input_path, segment_duration_secs)
+    })
+    .await??;
+    drop(input_file);
+
+    println!("分段打包完成: {} 个文件", files.len());
+
+    let parts = files
+        .into_iter()
+        .map(|(name, data)| {
+            let content_type = if name.ends_with(".mpd") {
+                "application/dash+xml"
+            } else if name.ends_with(".m3u8") {
+                "application/vnd.apple.mpegurl"
+            } else {
+                "video/iso.segment"
+            }
+            .to_string();
+            (name.clone(), name, content_type, data)
+        })
+        .collect();
+    let response = multipart_response(parts)?;
+
+    println!("响应发送完成");
     Ok(response)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    println!("🚀 启动 FFMPEG 分离服务器 V2");
+    println!("🚀 启动 FFMPEG 分离服务器 V3");
     println!("📋 系统信息:");
     println!("  - 监听端口: 8080");
-    println!("  - 支持并行处理: 是");
-    println!("  - FFmpeg版本: 7.1");
-    
-    // 检查FFmpeg是否可用
-    match tokio::process::Command::new("ffmpeg").arg("-version").output().await {
-        Ok(output) => {
-            if output.status.success() {
-                let version_info = String::from_utf8_lossy(&output.stdout);
-                let first_line = version_info.lines().next().unwrap_or("未知版本");
-                println!("✅ FFmpeg检查通过: {}", first_line);
-            } else {
-                eprintln!("❌ FFmpeg版本检查失败");
-                return Err("FFmpeg不可用".into());
+    println!("  - 分离方式: 进程内 libav（单次 demux，输出走自定义 AVIO，无子进程；上传流式写入临时文件）");
+    println!("  - 支持端点: / (音视频分离), /segment (DASH/HLS 分段打包)");
+    unsafe {
+        let version = ffmpeg_sys_next::av_version_info();
+        let version = std::ffi::CStr::from_ptr(version).to_string_lossy();
+        println!("  - libav 版本: {}", version);
+    }
+
+    // 可选：启动 SRT 直播接入（设置 SRT_LISTEN_PORT 才会监听）
+    if let Ok(port) = std::env::var("SRT_LISTEN_PORT") {
+        let port: u16 = port.parse().map_err(|_| "SRT_LISTEN_PORT 不是合法的端口号")?;
+        let segments: live::SegmentRing = std::sync::Arc::new(std::sync::Mutex::new(Default::default()));
+        tokio::spawn(async move {
+            if let Err(e) = live::run(port, segments).await {
+                eprintln!("❌ SRT 直播接入退出: {}", e);
             }
-        }
-        Err(e) => {
-            eprintln!("❌ 无法执行FFmpeg: {}", e);
-            return Err("FFmpeg不可执行".into());
-        }
+        });
     }
-    
+
     // 创建服务
     let make_svc = make_service_fn(|_conn| async {
         Ok::<_, hyper::Error>(service_fn(handle_request))
@@ -0,0 +1,44 @@
+//! AVIO source for live ingest: libav's (synchronous) read callback pulls
+//! chunks off a bounded `tokio::sync::mpsc::Receiver<Vec<u8>>` by blocking on
+//! the runtime handle captured at construction time. Used from the blocking
+//! task that owns the demux loop in [`crate::live`], so memory stays bounded
+//! by the channel capacity regardless of how long the stream runs.
+
+use ffmpeg_sys_next::AVERROR_EOF;
+use std::ffi::c_void;
+use std::os::raw::c_int;
+use std::ptr;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::Receiver;
+
+pub struct ChannelIo {
+    handle: Handle,
+    rx: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelIo {
+    pub fn new(handle: Handle, rx: Receiver<Vec<u8>>) -> Box<Self> {
+        Box::new(Self { handle, rx, pending: Vec::new(), pos: 0 })
+    }
+}
+
+pub unsafe extern "C" fn read_packet(opaque: *mut c_void, buf: *mut u8, buf_size: c_int) -> c_int {
+    let io = &mut *(opaque as *mut ChannelIo);
+    if io.pos >= io.pending.len() {
+        // 阻塞在 channel 上，直到有新的一批 MPEG-TS 数据，或发布者断开（通道关闭）
+        match io.handle.clone().block_on(io.rx.recv()) {
+            Some(chunk) => {
+                io.pending = chunk;
+                io.pos = 0;
+            }
+            None => return AVERROR_EOF,
+        }
+    }
+    let remaining = io.pending.len() - io.pos;
+    let n = remaining.min(buf_size as usize);
+    ptr::copy_nonoverlapping(io.pending[io.pos..].as_ptr(), buf, n);
+    io.pos += n;
+    n as c_int
+}